@@ -1,11 +1,11 @@
-use std::{io::{Read, Write}, time::Duration};
+use std::{fs, io::{Read, Write}, path::PathBuf, time::Duration};
 
 use console::style;
 use indicatif::ProgressBar;
 use vexv5_serial::device::VexDevice;
 use anyhow::Result;
 
-use crate::util;
+use crate::util::{self, WriteOptions};
 
 
 pub fn upload_file<T: Read + Write>(device: &mut VexDevice<T>, file_name: String, data: Vec<u8>) -> Result<()> {
@@ -15,6 +15,27 @@ pub fn upload_file<T: Read + Write>(device: &mut VexDevice<T>, file_name: String
 
     println!("{}", style("Uploading File "));
 
+    let crc = crc::Crc::<u32>::new(&vexv5_serial::protocol::VEX_CRC32).checksum(&data);
+
+    // Resume support: diff `data` against whatever's already on the brain.
+    // A whole-file match skips the transfer entirely; a partial match is
+    // handed to `write_file_progress` below, which skips re-sending whatever
+    // blocks still read back unchanged once the write handle is open. A
+    // failure to open the existing file for reading (most commonly:
+    // `file_name` doesn't exist on the brain yet) is treated as
+    // `ResumePlan::Full` by `diff_against_device` itself, so the first-ever
+    // upload of a file just falls through to a full upload. Errors while
+    // reading back or closing an existing file are returned.
+    let resume_plan = util::diff_against_device(device, &file_name, &data, crc)?;
+    if let util::ResumePlan::UpToDate = resume_plan {
+        println!("\x1b[F\x1b[32m✔\x1b[0m {} {} {}",
+            style("Already up to date, skipped uploading").bold(),
+            style(&file_name).cyan().bright(),
+            style(format!("in {:.3} seconds", std::time::SystemTime::now().duration_since(time)?.as_secs_f32())).bold()
+        );
+        return Ok(());
+    }
+
     // Write to the slot_1.ini file on the brain
     let mut fh = device.open(file_name.to_string(), Some(vexv5_serial::device::VexInitialFileMetadata {
         function: vexv5_serial::device::VexFileMode::Upload(vexv5_serial::device::VexFileTarget::FLASH, true),
@@ -22,18 +43,19 @@ pub fn upload_file<T: Read + Write>(device: &mut VexDevice<T>, file_name: String
         options: 0,
         length: data.len() as u32,
         addr: 0x3800000,
-        crc: crc::Crc::<u32>::new(&vexv5_serial::protocol::VEX_CRC32).checksum(&data),
+        crc,
         r#type: *b"bin\0",
         timestamp: 0,
         version: 0x01000000,
         linked_name: None,
     }))?;
 
-    
+
 
     // Write data
-    util::write_file_progress(&mut fh, data)?;
-    
+    let write_options = WriteOptions::default();
+    util::write_file_progress(&mut fh, data, write_options, &resume_plan)?;
+
     // We are doing a file transfer, so it may take some time for the final response.
     // Just increase the timeout here
     device.set_timeout(Some(Duration::new(15, 0)));
@@ -65,5 +87,69 @@ pub fn upload_file<T: Read + Write>(device: &mut VexDevice<T>, file_name: String
         style(format!("in {:.3} seconds", std::time::SystemTime::now().duration_since(time)?.as_secs_f32())).bold()
     );
 
+    Ok(())
+}
+
+/// Downloads a file off of the brain and writes it to `output`, or to
+/// stdout if `output` is `None`. The natural companion to [`upload_file`] -
+/// useful for grabbing logs, competition data, or dumping an installed
+/// program's `.bin`.
+pub fn download_file<T: Read + Write>(device: &mut VexDevice<T>, file_name: String, output: Option<PathBuf>) -> Result<()> {
+
+    // Begin timer
+    let time = std::time::SystemTime::now();
+
+    println!("{}", style("Downloading File "));
+
+    // Open the file for reading. The length and crc are unknown up front;
+    // the device fills in the real `transfer_metadata` once the handle is open.
+    let mut fh = device.open(file_name.to_string(), Some(vexv5_serial::device::VexInitialFileMetadata {
+        function: vexv5_serial::device::VexFileMode::Download(vexv5_serial::device::VexFileTarget::FLASH, false),
+        vid: vexv5_serial::device::VexVID::USER,
+        options: 0,
+        length: 0,
+        addr: 0x3800000,
+        crc: 0,
+        r#type: *b"bin\0",
+        timestamp: 0,
+        version: 0x01000000,
+        linked_name: None,
+    }))?;
+
+    // Read data
+    let data = util::read_file_progress(&mut fh)?;
+
+    // We are doing a file transfer, so it may take some time for the final response.
+    // Just increase the timeout here
+    device.set_timeout(Some(Duration::new(15, 0)));
+
+    // We will also setup a spinner so the user knows that the application has not frozen.
+    let sp = ProgressBar::new_spinner();
+    sp.set_message("Closing file handle");
+    sp.enable_steady_tick(100);
+
+    // Close file
+    fh.close(vexv5_serial::device::VexFiletransferFinished::DoNothing)?;
+
+    // And stop the spinner
+    sp.finish_and_clear();
+    print!("\x1b[F\x1b[32m✔\x1b[0m Finished closing file handle in {:.3} seconds\n", std::time::SystemTime::now().duration_since(time)?.as_secs_f32());
+
+    // Reset the timeout to default
+    device.set_timeout(None);
+
+    // Write the downloaded data out to the requested destination
+    match output {
+        Some(path) => fs::write(&path, &data)?,
+        None => std::io::stdout().write_all(&data)?,
+    }
+
+    // Log that the file has been successfully downloaded
+    println!("\x1b[F\x1b[32m✔\x1b[0m {} {} {}",
+        style("Successfully downloaded file").bold(),
+        style(file_name).cyan().bright(),
+        style(format!("in {:.3} seconds", std::time::SystemTime::now().duration_since(time)?.as_secs_f32())).bold()
+    );
+
     Ok(())
 }
\ No newline at end of file
@@ -1,14 +1,14 @@
-use std::{io::{Read, Write}};
+use std::{io::{Read, Write}, net::{SocketAddr, TcpStream}};
 
 use serialport::SerialPortType;
-use vexv5_serial::{ports::{VexSerialInfo, VexSerialClass}, device::V5FileHandle};
+use vexv5_serial::{ports::{VexSerialInfo, VexSerialClass}, device::{VexDevice, V5FileHandle}};
 use console::style;
 use dialoguer::{
     Select,
     theme::ColorfulTheme
 };
 use indicatif::{ProgressBar, ProgressStyle};
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 
 
@@ -18,8 +18,24 @@ pub enum DevicePair {
     Double(VexSerialInfo, VexSerialInfo)
 }
 
+/// Where to connect to reach a Vex device: a discovered local serial port
+/// (or brain+controller pair), or a network endpoint such as a brain exposed
+/// over WiFi or a host-side serial-to-TCP relay. Pass to [`connect`] to
+/// actually open it.
+#[derive(Clone, Debug)]
+pub enum ConnectionTarget {
+    Serial(DevicePair),
+    Network(SocketAddr),
+}
+
+/// Finds a Vex device to connect to. If `network` is given, it is used
+/// directly and local serial ports are not even discovered; otherwise this
+/// falls back to the existing serial port discovery/selection behavior.
+pub fn find_devices(network: Option<SocketAddr>) -> Result<ConnectionTarget> {
+    if let Some(addr) = network {
+        return Ok(ConnectionTarget::Network(addr));
+    }
 
-pub fn find_devices() -> Result<DevicePair> {
     // Try to find vex devices
     let devices = vexv5_serial::ports::discover_vex_ports()?;
 
@@ -93,21 +109,192 @@ pub fn find_devices() -> Result<DevicePair> {
         pairs[selection].clone()
     };
 
-    Ok(device)
+    Ok(ConnectionTarget::Serial(device))
+}
+
+/// Anything `Read + Write`, boxed up so [`connect`] can hand back the same
+/// `VexDevice<_>` type regardless of whether the underlying link ended up
+/// being a serial port or a TCP socket.
+pub trait ReadWrite: Read + Write {}
+impl<T: Read + Write> ReadWrite for T {}
+
+/// Opens whatever transport a [`ConnectionTarget`] resolved to and wraps it
+/// in a `VexDevice`, so `upload_file`/`download_file` work unchanged whether
+/// the link is USB serial or a network socket.
+///
+/// The network case uses a plain, synchronous `std::net::TcpStream`, since
+/// `VexDevice<T>` requires `T: Read + Write`.
+pub fn connect(target: ConnectionTarget) -> Result<VexDevice<Box<dyn ReadWrite>>> {
+    let transport: Box<dyn ReadWrite> = match target {
+        ConnectionTarget::Network(addr) => {
+            Box::new(TcpStream::connect(addr).context("connecting to brain over the network")?)
+        }
+        ConnectionTarget::Serial(DevicePair::Single(info)) => {
+            Box::new(serialport::new(info.port_info.port_name, 115200).open()
+                .context("opening serial port")?)
+        }
+        ConnectionTarget::Serial(DevicePair::Double(_, user)) => {
+            // Same PROS-matching convention as find_devices: the second port
+            // in a pair is the user port.
+            Box::new(serialport::new(user.port_info.port_name, 115200).open()
+                .context("opening serial port")?)
+        }
+    };
+
+    VexDevice::new(transport)
+}
+
+/// Tuning knobs for [`write_file_progress`].
+///
+/// Scope note: the request behind this struct asked for windowed/pipelined
+/// writes - multiple `write_some` calls in flight at once, with acks
+/// consumed asynchronously as they arrive. That is not what this delivers,
+/// and it's not achievable against the API this module actually has to
+/// work with: `write_some` bundles the send and the wait for its ack into
+/// a single blocking call, with no lower-level primitive exposed to split
+/// them. `V5FileHandle` would need to grow that primitive (or `VexDevice`
+/// would need to stop being `Read + Write`-blocking) before genuine
+/// pipelining is possible here. A `window` field and a lookahead `VecDeque`
+/// used to sit on this struct, but with every write still serialized
+/// one-at-a-time underneath, they only added bookkeeping for zero
+/// throughput benefit - so the scope actually delivered, and all this
+/// struct configures, is bounded per-chunk retry.
+#[derive(Debug, Clone, Copy)]
+pub struct WriteOptions {
+    /// How many times to retry a single chunk's write before giving up and
+    /// returning an error, so a permanently failing chunk (device unplugged,
+    /// persistent NACK) can't hang the upload forever.
+    pub max_retries: usize,
+}
+
+impl Default for WriteOptions {
+    fn default() -> WriteOptions {
+        WriteOptions { max_retries: 5 }
+    }
+}
+
+/// The packet-size "budget" used when slicing a transfer into chunks: 3/4 of
+/// the handle's max packet size, leaving headroom for packet headers. Shared
+/// between the write and read paths so their chunk sizing stays consistent.
+fn chunk_size(max_packet_size: u16) -> u16 {
+    max_packet_size / 2 + (max_packet_size / 4)
+}
+
+/// What [`diff_against_device`] found when comparing `data` against whatever
+/// is already stored on the brain.
+#[derive(Debug, Clone)]
+pub enum ResumePlan {
+    /// No existing file to compare against (including "it doesn't exist
+    /// yet" - the common first-upload case, where opening it for `Download`
+    /// fails outright), or its size doesn't match `data`'s. Upload in full.
+    Full,
+    /// Whole-file size and CRC32 both match - skip the upload entirely.
+    UpToDate,
+    /// Same size, different whole-file CRC32, so the two differ somewhere.
+    /// `unchanged[i]` says whether block `i` (of `block_size` bytes each,
+    /// the same sizing [`chunk_size`] uses, derived from the *download*
+    /// handle's `max_packet_size`) read back identical to `data`'s block.
+    ///
+    /// This only means the block was unchanged as of this read - it is a
+    /// candidate to skip, not a guarantee. `upload_file` opens the write
+    /// handle with the overwrite flag set, which erases the declared target
+    /// region up front, so a block can read back identical here and still
+    /// be gone by the time the write handle is open. [`write_file_progress`]
+    /// re-confirms each candidate by reading it back again through the
+    /// *upload* handle before actually skipping its write.
+    Partial { block_size: u32, unchanged: Vec<bool> },
+}
+
+/// Compares `data` against whatever is already stored as `file_name` on the
+/// brain: whole-file size and CRC32 first, then - if the sizes match but the
+/// CRCs don't - a per-block readback to find which blocks already match.
+///
+/// Returns [`ResumePlan::Full`] (not an error) when there's no existing file
+/// to compare against, e.g. this is the first time `file_name` has ever been
+/// uploaded, so opening it for `Download` fails. The caller doesn't need to
+/// distinguish that from any other reason a full upload is needed.
+pub fn diff_against_device<T: Read + Write>(device: &mut VexDevice<T>, file_name: &str, data: &[u8], data_crc: u32) -> Result<ResumePlan> {
+    let mut fh = match device.open(file_name.to_string(), Some(vexv5_serial::device::VexInitialFileMetadata {
+        function: vexv5_serial::device::VexFileMode::Download(vexv5_serial::device::VexFileTarget::FLASH, false),
+        vid: vexv5_serial::device::VexVID::USER,
+        options: 0,
+        length: 0,
+        addr: 0x3800000,
+        crc: 0,
+        r#type: *b"bin\0",
+        timestamp: 0,
+        version: 0x01000000,
+        linked_name: None,
+    })) {
+        Ok(fh) => fh,
+        // Most commonly: `file_name` has never been uploaded before, so
+        // there's nothing on the brain to open for Download. Whatever the
+        // reason, there's no existing file to diff against, so fall back to
+        // a full upload rather than bubbling the error up.
+        Err(_) => return Ok(ResumePlan::Full),
+    };
+
+    // Note `fh.metadata` is just the `VexInitialFileMetadata` passed into
+    // `open` above (with `crc: 0`, since we don't know it yet) - the
+    // device-reported CRC of what's actually on the brain lands in
+    // `transfer_metadata`, same as `file_size` below.
+    if fh.transfer_metadata.file_size == data.len() as u32 && fh.transfer_metadata.crc == data_crc {
+        fh.close(vexv5_serial::device::VexFiletransferFinished::DoNothing)?;
+        return Ok(ResumePlan::UpToDate);
+    }
+
+    if fh.transfer_metadata.file_size != data.len() as u32 {
+        fh.close(vexv5_serial::device::VexFiletransferFinished::DoNothing)?;
+        return Ok(ResumePlan::Full);
+    }
+
+    // Same size, different CRC: read the existing file back one block at a
+    // time and diff each block against `data`, so the caller can skip
+    // re-sending whichever blocks already match.
+    let block_size = chunk_size(fh.transfer_metadata.max_packet_size) as u32;
+    let mut unchanged = Vec::with_capacity((data.len() / block_size.max(1) as usize) + 1);
+
+    for i in (0..data.len()).step_by(block_size as usize) {
+        let len = block_size.min((data.len() - i) as u32) as u16;
+        let onboard = fh.read_some(fh.metadata.addr + i as u32, len)?;
+        unchanged.push(onboard == data[i..i + len as usize]);
+    }
+
+    fh.close(vexv5_serial::device::VexFiletransferFinished::DoNothing)?;
+
+    Ok(ResumePlan::Partial { block_size, unchanged })
 }
 
-/// Writes a vector up to the file length of data to the file. 
+/// Writes a vector up to the file length of data to the file.
 /// Ignores any extra bytes at the end of the vector.
 /// Returns the ammount of data read
 /// Same as the function provided in vexv5_serial but it shows progress to the user.
-pub fn write_file_progress<T: Read + Write>(handle: &mut V5FileHandle<T>, data: Vec<u8>) -> Result<usize> {
+///
+/// `resume_plan` is whatever [`diff_against_device`] found before this
+/// handle was opened. For [`ResumePlan::Partial`], its `unchanged` blocks
+/// are re-confirmed here - by reading each one back through this *upload*
+/// handle and comparing against `data` again - before being skipped, since
+/// opening this handle (with the overwrite flag set) erases the declared
+/// target region and may have invalidated what `diff_against_device` saw.
+/// A block that no longer reads back clean, or can't be read back at all,
+/// is written rather than skipped; `resume_plan` only ever narrows what
+/// gets written, never forces a skip. Iterates in `resume_plan`'s
+/// `block_size`, not this handle's own chunk sizing, so the two stages
+/// never disagree about where one block ends and the next begins.
+pub fn write_file_progress<T: Read + Write>(handle: &mut V5FileHandle<T>, data: Vec<u8>, options: WriteOptions, resume_plan: &ResumePlan) -> Result<usize> {
 
     // Save the max size so it is easier to access
     // We want it to be 3/4 size so we do not have issues with packet headers
     // going over the max size
-    let max_size = handle.transfer_metadata.max_packet_size / 
-    2 + (handle.transfer_metadata.max_packet_size / 4);
-    
+    let max_size = match resume_plan {
+        ResumePlan::Partial { block_size, .. } => *block_size as u16,
+        ResumePlan::Full | ResumePlan::UpToDate => chunk_size(handle.transfer_metadata.max_packet_size),
+    };
+    let unchanged: &[bool] = match resume_plan {
+        ResumePlan::Partial { unchanged, .. } => unchanged,
+        ResumePlan::Full | ResumePlan::UpToDate => &[],
+    };
+
     // We will be using the length of the file in the metadata
     // that way we do not ever write more data than is expected.
     // However, if the vector is smaller than the file size
@@ -118,11 +305,11 @@ pub fn write_file_progress<T: Read + Write>(handle: &mut V5FileHandle<T>, data:
         data.len() as u32
     };
 
-    
+
 
     // We will be incrementing this variable so we know how much we have written
     let mut how_much: usize = 0;
-    
+
     // Create the progress bar
     let bar = ProgressBar::new(size.into());
 
@@ -131,9 +318,11 @@ pub fn write_file_progress<T: Read + Write>(handle: &mut V5FileHandle<T>, data:
         .template("[{elapsed_precise}] {binary_bytes_per_sec} {bar:40.cyan/blue} {percent}% {bytes:>7}/{total_bytes:7} {msg}")
         .progress_chars("##-"));
 
-    // Iterate over the file's length in steps of max_size
-    // We will be writing each iteration.
-    for i in (0..size as usize).step_by(max_size.into()) {
+    // Each chunk is sliced out of `data` and written in turn - `write_some`
+    // bundles the send and the wait for its ack into a single blocking call,
+    // so there is no way to have more than one chunk's write outstanding at
+    // a time.
+    for (block_idx, i) in (0..size as usize).step_by(max_size.into()).enumerate() {
         // Determine the packet size. We do not want to write
         // max_size bytes if we are at the end of the file
         let packet_size = if size < max_size as u32 {
@@ -146,11 +335,50 @@ pub fn write_file_progress<T: Read + Write>(handle: &mut V5FileHandle<T>, data:
 
         // Cut out packet_size bytes out of the provided buffer
         let payload = data[i..i+packet_size as usize].to_vec();
+        let addr = handle.metadata.addr + i as u32;
 
-        // Write the payload to the file
-        handle.write_some(handle.metadata.addr + i as u32, payload)?;
+        // Re-confirm a block `diff_against_device` flagged unchanged by
+        // reading it back through *this* handle, post-erase, rather than
+        // trusting the pre-open diff blindly. If it still reads back
+        // identical, skip writing it; otherwise (including a read error -
+        // erased flash may not even be readable the same way) fall through
+        // and write it like any other block.
+        if unchanged.get(block_idx).copied().unwrap_or(false) {
+            if let Ok(onboard) = handle.read_some(addr, packet_size) {
+                if onboard == payload {
+                    bar.inc(packet_size.into());
+                    how_much += packet_size as usize;
+                    continue;
+                }
+            }
+        }
 
-        // Update the progress bar
+        // Retry a chunk in place if the device NACKs it or the CRC16 on its
+        // response doesn't check out, rather than aborting the whole upload.
+        // Bounded, so a chunk that fails forever (device unplugged, a
+        // persistent NACK) errors out instead of hanging the CLI.
+        let mut attempts = 0;
+        loop {
+            match handle.write_some(addr, payload.clone()) {
+                Ok(_) => break,
+                Err(e) => {
+                    attempts += 1;
+                    if attempts > options.max_retries {
+                        bar.abandon();
+                        return Err(e.context(format!(
+                            "giving up on chunk at {:#010x} after {} attempts",
+                            addr, attempts
+                        )));
+                    }
+                    bar.println(format!(
+                        "Retrying chunk at {:#010x} after write error ({}/{}): {}",
+                        addr, attempts, options.max_retries, e
+                    ));
+                }
+            }
+        }
+
+        // Update the progress bar now that the chunk's write is confirmed
         bar.inc(packet_size.into());
 
         // Increment how_much by packet data so we know how much we
@@ -162,4 +390,178 @@ pub fn write_file_progress<T: Read + Write>(handle: &mut V5FileHandle<T>, data:
     bar.finish();
 
     Ok(how_much)
+}
+
+/// How many times [`read_file_progress`] retries a single chunk whose
+/// length doesn't match what was asked for, before giving up. Mirrors
+/// [`WriteOptions::max_retries`]'s default - there's no per-read equivalent
+/// of [`WriteOptions`] to configure this from, since nothing has needed to
+/// tune it yet.
+const MAX_CHUNK_READ_RETRIES: usize = 5;
+
+/// Checks that a chunk read back from the device is the length it was asked
+/// for. `read_some` can return fewer bytes than requested without itself
+/// erroring (e.g. the link drops mid-response) - verifying the length here
+/// catches that as a truncated chunk instead of silently writing a short
+/// (and therefore corrupt) chunk into the downloaded file.
+fn verify_chunk(addr: u32, expected_len: u16, chunk: &[u8]) -> Result<()> {
+    if chunk.len() != expected_len as usize {
+        anyhow::bail!(
+            "truncated chunk at {:#010x}: expected {} bytes, got {}",
+            addr, expected_len, chunk.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Reads the file an already-opened handle points at, in chunks of 3/4 the
+/// handle's `max_packet_size` ([`chunk_size`]), showing the same progress bar styling as
+/// [`write_file_progress`]. Each chunk is verified ([`verify_chunk`]) before
+/// being accepted, with a bounded retry on a chunk that comes back short.
+/// Returns the downloaded file contents.
+pub fn read_file_progress<T: Read + Write>(handle: &mut V5FileHandle<T>) -> Result<Vec<u8>> {
+
+    // Same 3/4 sizing as write_file_progress, for the same reason: leave
+    // room for packet headers.
+    let max_size = chunk_size(handle.transfer_metadata.max_packet_size);
+
+    // The device fills in the real file size once the handle is open in
+    // download mode, so read exactly that much.
+    let size = handle.transfer_metadata.file_size;
+
+    let mut data: Vec<u8> = Vec::with_capacity(size as usize);
+
+    // Create the progress bar
+    let bar = ProgressBar::new(size.into());
+
+    // Style the progress bar
+    bar.set_style(ProgressStyle::default_bar()
+        .template("[{elapsed_precise}] {binary_bytes_per_sec} {bar:40.cyan/blue} {percent}% {bytes:>7}/{total_bytes:7} {msg}")
+        .progress_chars("##-"));
+
+    // Iterate over the file's length in steps of max_size, reading each
+    // chunk in turn.
+    for i in (0..size as usize).step_by(max_size.into()) {
+        // Determine the packet size. We do not want to read
+        // max_size bytes if we are at the end of the file
+        let packet_size = if size < max_size as u32 {
+            size as u16
+        } else if i as u32 + max_size as u32 > size {
+            (size - i as u32) as u16
+        } else {
+            max_size
+        };
+
+        // Read packet_size bytes starting at this chunk's address, retrying
+        // in place if the chunk comes back truncated - the same bounded
+        // approach write_file_progress takes with a failed write.
+        let addr = handle.metadata.addr + i as u32;
+        let mut attempts = 0;
+        let chunk = loop {
+            let chunk = handle.read_some(addr, packet_size)?;
+            match verify_chunk(addr, packet_size, &chunk) {
+                Ok(()) => break chunk,
+                Err(e) => {
+                    attempts += 1;
+                    if attempts > MAX_CHUNK_READ_RETRIES {
+                        bar.abandon();
+                        return Err(e.context(format!(
+                            "giving up on chunk at {:#010x} after {} attempts",
+                            addr, attempts
+                        )));
+                    }
+                    bar.println(format!(
+                        "Retrying chunk at {:#010x} after verification error ({}/{}): {}",
+                        addr, attempts, MAX_CHUNK_READ_RETRIES, e
+                    ));
+                }
+            }
+        };
+        data.extend_from_slice(&chunk);
+
+        // Update the progress bar
+        bar.inc(packet_size.into());
+    }
+
+    // Finalize the progress bar
+    bar.finish();
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    /// A transport that never produces any bytes and accepts writes into
+    /// the void - stands in for "nothing is actually connected", the same
+    /// condition the brain's serial link is in from `VexDevice`'s point of
+    /// view when asked to open a file that was never uploaded: there is no
+    /// well-formed response coming back, ever.
+    struct NullTransport;
+
+    impl Read for NullTransport {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Ok(0)
+        }
+    }
+
+    impl Write for NullTransport {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// `upload_file`'s resume path opens the target file for `Download`
+    /// (via `diff_against_device`) and, unless that says the file is
+    /// already up to date, immediately opens it again for `Upload`. The
+    /// most common way `Download` fails is the most common case there is:
+    /// this is the first time the file has ever been uploaded, so there's
+    /// nothing on the brain to open. This exercises that whole sequence -
+    /// failed `Download` open, then `Upload` open right after - against a
+    /// transport that can never produce a valid response, and checks
+    /// neither step panics or hangs, and that the failed `Download` maps to
+    /// `ResumePlan::Full` rather than an error reaching the caller.
+    #[test]
+    fn download_then_upload_open_sequence_on_a_missing_file() {
+        let mut device = VexDevice::new(NullTransport)
+            .expect("constructing a VexDevice over a stub transport should not itself fail");
+
+        let plan = diff_against_device(&mut device, "never_uploaded.bin", b"data", 0)
+            .expect("a missing file should fall back to ResumePlan::Full, not an error");
+        assert!(matches!(plan, ResumePlan::Full));
+
+        let upload_open = device.open("never_uploaded.bin".to_string(), Some(vexv5_serial::device::VexInitialFileMetadata {
+            function: vexv5_serial::device::VexFileMode::Upload(vexv5_serial::device::VexFileTarget::FLASH, true),
+            vid: vexv5_serial::device::VexVID::USER,
+            options: 0,
+            length: 4,
+            addr: 0x3800000,
+            crc: 0,
+            r#type: *b"bin\0",
+            timestamp: 0,
+            version: 0x01000000,
+            linked_name: None,
+        }));
+        assert!(upload_open.is_err());
+    }
+
+    #[test]
+    fn verify_chunk_accepts_a_full_length_chunk() {
+        assert!(verify_chunk(0x3800000, 4, &[1, 2, 3, 4]).is_ok());
+    }
+
+    #[test]
+    fn verify_chunk_rejects_a_truncated_chunk() {
+        // Simulates a link drop that hands back fewer bytes than requested
+        // without `read_some` itself erroring - the case this exists to
+        // catch.
+        assert!(verify_chunk(0x3800000, 4, &[1, 2]).is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,188 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::io::{AllowStdIo, AsyncRead, AsyncWrite};
+
+/// Anything that can carry the Vex serial protocol. This used to be
+/// `std::io::{Read, Write}`, which hard-wires `VexProtocolWrapper` to
+/// blocking transports and rules out a WASM build entirely. Implementing
+/// against the async traits instead means the same protocol code could run
+/// over `serialport` on the CLI (via [`Blocking`]) or over WebSerial in a
+/// browser (via `web::WebSerialTransport`) - nothing currently constructs
+/// either of those outside this module, since `files.rs`/`util.rs` drive the
+/// external, synchronous `vexv5_serial::device::VexDevice` instead.
+pub trait VexTransport: AsyncRead + AsyncWrite + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Unpin> VexTransport for T {}
+
+/// Bridges a blocking [`std::io::Read`] + [`std::io::Write`] device (such as
+/// a `serialport::SerialPort`) into a [`VexTransport`].
+///
+/// Reads and writes still block the calling thread; this lets a synchronous
+/// backend such as `serialport` drive the now-async `VexProtocolWrapper`
+/// without being rewritten.
+pub struct Blocking<T>(AllowStdIo<T>);
+
+impl<T> Blocking<T> {
+    pub fn new(inner: T) -> Self {
+        Blocking(AllowStdIo::new(inner))
+    }
+}
+
+impl<T: io::Read> AsyncRead for Blocking<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl<T: io::Write> AsyncWrite for Blocking<T> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_close(cx)
+    }
+}
+
+/// Async transport over a plain TCP socket, for a brain exposed on the
+/// network (WiFi, or a host-side serial-to-TCP relay) that speaks the exact
+/// same simple/extended packet framing as a local serial link - analogous to
+/// a Modbus stack layering the same PDU encoding over serial and TCP alike.
+///
+/// This is the transport for the local async `VexProtocolWrapper`, not for
+/// the CLI's upload/download paths - those drive the external, synchronous
+/// `vexv5_serial::device::VexDevice<T: Read + Write>`, which a plain
+/// `std::net::TcpStream` satisfies directly and `async_net::TcpStream` does
+/// not. `util::connect` uses the sync stream for that reason.
+pub mod tcp {
+    use std::io;
+    use std::net::ToSocketAddrs;
+
+    use async_net::TcpStream;
+
+    /// Connects to `addr` and returns a [`super::VexTransport`] ready to
+    /// hand straight to `VexProtocolWrapper::new`. `async_net::TcpStream`
+    /// already implements `AsyncRead + AsyncWrite`, so unlike the blocking
+    /// `serialport` backend, no [`super::Blocking`] adapter is needed here.
+    pub async fn connect(addr: impl ToSocketAddrs) -> io::Result<TcpStream> {
+        let addrs: Vec<_> = addr.to_socket_addrs()?.collect();
+        TcpStream::connect(&*addrs).await
+    }
+}
+
+/// Async transport backed by the browser WebSerial API - the transport the
+/// same `VexProtocolWrapper`, packet framing, and CRC code would run over in
+/// a wasm-bindgen web page. Nothing outside the `v5` module drives this yet;
+/// `files.rs`/`util.rs` still talk to the external, synchronous
+/// `vexv5_serial::device::VexDevice`, so wiring a browser build up to this
+/// transport (and to `VexProtocolWrapper` in general) is future work.
+#[cfg(target_arch = "wasm32")]
+pub mod web {
+    use std::future::Future;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures::io::{AsyncRead, AsyncWrite};
+    use js_sys::Uint8Array;
+    use wasm_bindgen::prelude::*;
+    use wasm_bindgen_futures::JsFuture;
+    use web_sys::{ReadableStreamDefaultReader, SerialOptions, SerialPort, WritableStreamDefaultWriter};
+
+    type PendingJsResult = Pin<Box<dyn Future<Output = Result<JsValue, JsValue>>>>;
+
+    /// A [`super::VexTransport`] over a browser `SerialPort`, as returned by
+    /// `navigator.serial.requestPort()`.
+    pub struct WebSerialTransport {
+        reader: ReadableStreamDefaultReader,
+        writer: WritableStreamDefaultWriter,
+        // Bytes read from the stream but not yet consumed by `poll_read`,
+        // since the stream hands back whole chunks rather than exact sizes.
+        pending: Vec<u8>,
+        // The in-flight `reader.read()`/`writer.write()` call, if `poll_read`
+        // or `poll_write` returned `Pending` last time and is waiting to be
+        // polled again rather than starting a fresh call.
+        read_fut: Option<PendingJsResult>,
+        write_fut: Option<PendingJsResult>,
+    }
+
+    impl WebSerialTransport {
+        /// Opens `port` at 115200 baud and locks its readable/writable
+        /// streams for exclusive use by this transport.
+        pub async fn open(port: SerialPort) -> Result<Self, JsValue> {
+            JsFuture::from(port.open(&SerialOptions::new(115200))).await?;
+
+            let reader: ReadableStreamDefaultReader = port.readable().get_reader().unchecked_into();
+            let writer: WritableStreamDefaultWriter = port.writable().get_writer()?;
+
+            Ok(WebSerialTransport { reader, writer, pending: Vec::new(), read_fut: None, write_fut: None })
+        }
+    }
+
+    fn js_err(e: JsValue) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, format!("{:?}", e))
+    }
+
+    impl AsyncRead for WebSerialTransport {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            if self.pending.is_empty() {
+                // Poll the browser's own `Promise`-backed future directly
+                // through `JsFuture`'s `Future` impl, instead of blocking the
+                // (single, main) wasm thread on it - blocking here would
+                // deadlock, since nothing else would ever run to resolve it.
+                if self.read_fut.is_none() {
+                    self.read_fut = Some(Box::pin(JsFuture::from(self.reader.read())));
+                }
+
+                let chunk = match self.read_fut.as_mut().unwrap().as_mut().poll(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(result) => {
+                        self.read_fut = None;
+                        result.map_err(js_err)?
+                    }
+                };
+
+                let value = js_sys::Reflect::get(&chunk, &JsValue::from_str("value")).map_err(js_err)?;
+                if !value.is_undefined() {
+                    self.pending = Uint8Array::new(&value).to_vec();
+                }
+            }
+
+            let n = std::cmp::min(buf.len(), self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    impl AsyncWrite for WebSerialTransport {
+        fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            if self.write_fut.is_none() {
+                let array = Uint8Array::from(buf);
+                self.write_fut = Some(Box::pin(JsFuture::from(self.writer.write_with_chunk(&array))));
+            }
+
+            match self.write_fut.as_mut().unwrap().as_mut().poll(cx) {
+                Poll::Pending => Poll::Pending,
+                Poll::Ready(result) => {
+                    self.write_fut = None;
+                    result.map_err(js_err)?;
+                    Poll::Ready(Ok(buf.len()))
+                }
+            }
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+}
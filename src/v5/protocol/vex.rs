@@ -1,9 +1,11 @@
-use std::io::{Read, Write};
 use anyhow::{Result, anyhow};
+use futures::io::{AsyncReadExt, AsyncWriteExt};
 use num_derive::FromPrimitive;
 use std::time::{Duration, SystemTime};
 use crc::Algorithm;
 
+use super::transport::VexTransport;
+
 
 const VEX_CRC16: Algorithm<u16> = Algorithm {
     poly: 0x1021,
@@ -26,7 +28,7 @@ pub enum VexDeviceType {
 
 /// Represents a vex device command
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, FromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
 pub enum VexDeviceCommand {
     ExecuteFile = 0x18,
     Extended = 0x56,
@@ -47,16 +49,108 @@ impl PartialEq<VexDeviceCommand> for u8 {
 }
 
 
-/// Wraps any struct that implements both read and write
-/// traits. Allows sending vex device commands. 
-pub struct VexProtocolWrapper<T> 
-    where T: Read + Write {
+/// Status the brain reports instead of success for an `Extended` command,
+/// decoded from the ack/nack byte that follows the echoed command code in
+/// the response payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VexNackError {
+    General,
+    CrcError,
+    PayloadTooSmall,
+    TransferSizeTooLarge,
+    ProgramCrcFailed,
+    ProgramFileError,
+    UninitializedTransfer,
+    InvalidInitialization,
+    DataNotSpooled,
+    TransferTimedOut,
+    Unknown(u8),
+}
+
+impl VexNackError {
+    /// Maps a device ack/nack byte to a nack variant, or `None` if `byte` is
+    /// the ack byte itself (not an error).
+    fn from_byte(byte: u8) -> Option<VexNackError> {
+        Some(match byte {
+            0x76 => return None,
+            0xFF => VexNackError::General,
+            0xCE => VexNackError::CrcError,
+            0xD0 => VexNackError::PayloadTooSmall,
+            0xD1 => VexNackError::TransferSizeTooLarge,
+            0xD2 => VexNackError::ProgramCrcFailed,
+            0xD3 => VexNackError::ProgramFileError,
+            0xD4 => VexNackError::UninitializedTransfer,
+            0xD5 => VexNackError::InvalidInitialization,
+            0xD6 => VexNackError::DataNotSpooled,
+            0xD7 => VexNackError::TransferTimedOut,
+            other => VexNackError::Unknown(other),
+        })
+    }
+}
+
+impl std::fmt::Display for VexNackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VexNackError::General => write!(f, "device returned a general NACK"),
+            VexNackError::CrcError => write!(f, "device reported a CRC error on the packet it received"),
+            VexNackError::PayloadTooSmall => write!(f, "device reported the command payload was too small"),
+            VexNackError::TransferSizeTooLarge => write!(f, "device reported the requested transfer size was too large"),
+            VexNackError::ProgramCrcFailed => write!(f, "device reported the program CRC check failed"),
+            VexNackError::ProgramFileError => write!(f, "device reported a program file error"),
+            VexNackError::UninitializedTransfer => write!(f, "device reported the transfer was not initialized"),
+            VexNackError::InvalidInitialization => write!(f, "device reported an invalid initialization packet"),
+            VexNackError::DataNotSpooled => write!(f, "device reported the data was not spooled"),
+            VexNackError::TransferTimedOut => write!(f, "device reported the transfer timed out"),
+            VexNackError::Unknown(code) => write!(f, "device returned unknown nack code {:#04x}", code),
+        }
+    }
+}
+
+impl std::error::Error for VexNackError {}
+
+
+/// A minimal cursor over an in-memory response buffer, so decoding a fully
+/// buffered frame is a sequence of small reads instead of hand-rolled offset
+/// arithmetic.
+struct ProtoRead<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ProtoRead<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        ProtoRead { buf, pos: 0 }
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        let b = *self.buf.get(self.pos).ok_or_else(|| anyhow!("response frame ended early"))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    /// Returns everything after the cursor's current position.
+    fn remaining(&self) -> &'a [u8] {
+        &self.buf[self.pos..]
+    }
+}
+
+
+/// Wraps anything that implements [`VexTransport`] (async read + write).
+/// Allows sending vex device commands.
+///
+/// `T` used to be bound to blocking `std::io::{Read, Write}`, which made this
+/// wrapper (and everything built on it) unusable from WASM. It now drives an
+/// async transport instead, with [`super::transport::Blocking`] available to
+/// adapt the existing blocking `serialport` backend so the CLI path is
+/// unchanged.
+pub struct VexProtocolWrapper<T>
+    where T: VexTransport {
     device_type: VexDeviceType,
     wraps: T
 }
 
-impl<T> VexProtocolWrapper<T> 
-    where T: Read + Write {
+impl<T> VexProtocolWrapper<T>
+    where T: VexTransport {
 
     /// Initializes a new VexProtocolWrapper
     pub fn new(device_type: VexDeviceType, wraps: T) -> VexProtocolWrapper<T> {
@@ -67,18 +161,18 @@ impl<T> VexProtocolWrapper<T>
     }
 
     /// Sends an extended packet to the vex device
-    pub fn send_extended(&mut self, command: VexDeviceCommand, data: Vec<u8>) -> Result<usize> {
-        
+    pub async fn send_extended(&mut self, command: VexDeviceCommand, data: Vec<u8>) -> Result<usize> {
+
         // Create the payload
         let payload = self.create_extended_packet(command, data)?;
-        
+
         // Send the payload and return the length of the data sent
-        self.send_simple(VexDeviceCommand::Extended, payload)
+        self.send_simple(VexDeviceCommand::Extended, payload).await
     }
-    
+
 
     /// Receives a simple packet from the vex device
-    pub fn receive_simple(&mut self, timeout: Option<Duration>) -> Result<(VexDeviceCommand, Vec<u8>)> {
+    pub async fn receive_simple(&mut self, timeout: Option<Duration>) -> Result<(VexDeviceCommand, Vec<u8>)> {
 
         // Use default timeout if none was provided
         let timeout = match timeout {
@@ -95,10 +189,10 @@ impl<T> VexProtocolWrapper<T>
         let then = SystemTime::now() + timeout;
 
         // Iterate over recieving single bytes untill we recieve the header
-        while header_index < 3 {
+        while header_index < expected_header.len() {
             // Recieve a single byte
             let mut b: [u8; 1] = [0];
-            self.wraps.read_exact(&mut b)?;
+            self.wraps.read_exact(&mut b).await?;
 
             // If the byte is equivilent to the current index in expected header
             // then increment the current index. if not, then set it back to zero
@@ -109,35 +203,44 @@ impl<T> VexProtocolWrapper<T>
             }
 
             // If the timeout is elapsed then return an error
-            if !then.elapsed().unwrap_or(Duration::new(0, 0)).is_zero() && header_index < 3 {
+            if !then.elapsed().unwrap_or(Duration::new(0, 0)).is_zero() && header_index < expected_header.len() {
                 return Err(anyhow!("Unable to find response header in timeout, so unable to recieve data from device."));
             }
         }
 
-        // Read in the next two bytes
-        let mut buf: [u8; 2] = [0, 0];
-        self.wraps.read_exact(&mut buf)?;
+        // Read in the next two bytes. These are covered by an extended
+        // frame's CRC, so keep them around verbatim alongside the header.
+        let mut header_tail: Vec<u8> = vec![0, 0];
+        self.wraps.read_exact(&mut header_tail).await?;
 
         // Extract the command and the length of the packet
-        let command = buf[0];
-        let mut length: u16 = buf[1].into();
-        
-        // If this is an extended command
-        if command == VexDeviceCommand::Extended {
-            // Then extract the lower byte of the length
+        let command = header_tail[0];
+        let length_byte = header_tail[1];
+        let mut length: u16 = length_byte.into();
+
+        // An `Extended` response's length is 1 byte, unless the high bit of
+        // that byte is set, in which case it's a 2-byte length field: the
+        // low 7 bits of `length_byte` are the high bits of a 15-bit length,
+        // and a second byte carries the low 8 bits. Masking that bit off and
+        // only reading a second byte when it's actually set matters because
+        // a prior version always read a second byte for any `Extended`
+        // response - misreading every response whose length fit in one byte
+        // with the high bit clear, inflating `length`, and then hanging
+        // forever in the `read_exact` below waiting for bytes the device
+        // never sends.
+        if command == VexDeviceCommand::Extended && length_byte & 0x80 != 0 {
             let mut b: [u8; 1] = [0];
-            self.wraps.read_exact(&mut b)?;
-
-            let b: u16 = b[0].into();
+            self.wraps.read_exact(&mut b).await?;
+            header_tail.push(b[0]);
 
-            // And append it to the length
-            length <<= 8;
-            length |= b;
+            length = (((length_byte & 0x7f) as u16) << 8) | b[0] as u16;
         }
 
-        // Read in the rest of the payload
+        // Read in the rest of the payload. For an `Extended` response this
+        // includes the trailing 2-byte CRC16, verified below before any of
+        // it is trusted.
         let mut payload: Vec<u8> = vec![0; length.into()];
-        self.wraps.read(&mut payload)?; // We do not care about size here. Some commands may send less data than needed.
+        self.wraps.read_exact(&mut payload).await?;
 
         // Try to convert the command into it's enum format
         let command: VexDeviceCommand =  match num::FromPrimitive::from_u8(command) {
@@ -146,30 +249,76 @@ impl<T> VexProtocolWrapper<T>
                 return Err(anyhow!("Unknown command {}", command));
             }
         };
-        
+
+        // Extended responses carry their own CRC16 and an ack/nack status;
+        // verify both before handing the payload back to the caller.
+        let payload = if command == VexDeviceCommand::Extended {
+            self.verify_and_unwrap_extended(&expected_header, &header_tail, payload)?
+        } else {
+            payload
+        };
+
         // Return the command and the payload
         Ok((command, payload))
     }
 
+    /// Verifies the CRC16 trailer on a buffered `Extended` response and
+    /// decodes its ack/nack status byte, returning just the response payload
+    /// (echoed command, ack byte, and CRC stripped off) on success.
+    fn verify_and_unwrap_extended(&self, header: &[u8; 2], header_tail: &[u8], payload: Vec<u8>) -> Result<Vec<u8>> {
+        if payload.len() < 2 {
+            return Err(anyhow!("extended response too short to contain a CRC16 trailer"));
+        }
+
+        // Split off the trailing CRC16 so it can be recomputed over
+        // everything that came before it.
+        let (body, crc_bytes) = payload.split_at(payload.len() - 2);
+        let received_crc = u16::from_be_bytes([crc_bytes[0], crc_bytes[1]]);
+
+        let mut frame = Vec::with_capacity(header.len() + header_tail.len() + body.len());
+        frame.extend_from_slice(header);
+        frame.extend_from_slice(header_tail);
+        frame.extend_from_slice(body);
+
+        let computed_crc = crc::Crc::<u16>::new(&VEX_CRC16).checksum(&frame);
+
+        if computed_crc != received_crc {
+            return Err(anyhow!(
+                "CRC16 mismatch on extended response: expected {:#06x}, got {:#06x}",
+                computed_crc, received_crc
+            ));
+        }
+
+        // The first two bytes of the body are the echoed command and the
+        // ack/nack status.
+        let mut cursor = ProtoRead::new(body);
+        let _echoed_command = cursor.u8()?;
+        let status = cursor.u8()?;
+
+        if let Some(nack) = VexNackError::from_byte(status) {
+            return Err(anyhow::Error::new(nack));
+        }
+
+        Ok(cursor.remaining().to_vec())
+    }
+
     /// Sends a simple packet to the vex device
-    pub fn send_simple(&mut self, command: VexDeviceCommand, data: Vec<u8>) -> Result<usize>{
+    pub async fn send_simple(&mut self, command: VexDeviceCommand, data: Vec<u8>) -> Result<usize>{
 
         // Create the packet
         let mut packet = self.create_packet(command);
 
         // Add the data to the packet
         packet.append(&mut data.clone());
-        
-        
-        println!("{:?}", packet);
+
         // Write the data
-        self.wraps.write_all(&mut packet)?;
-        
+        self.wraps.write_all(&mut packet).await?;
+
 
         // Flush all pending writes on the buffer.
-        self.wraps.flush()?;
-        
-        
+        self.wraps.flush().await?;
+
+
         // Return the length of the data sent
         Ok(packet.len())
     }